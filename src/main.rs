@@ -1,14 +1,26 @@
 use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    convert::Infallible,
     sync::{Arc, RwLock},
-    thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use axum::{routing::get, Json, Router};
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::sse::{Event as SseEvent, Sse},
+    routing::{get, post, put},
+    Json, Router,
+};
+use futures::stream::Stream;
 use lazy_static::lazy_static;
 use log::info;
+use prometheus::{Encoder, IntCounter, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder};
 use rand::{rng, Rng};
 use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::StreamExt;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 enum Status {
@@ -18,7 +30,7 @@ enum Status {
     LoggedOut,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 enum Department {
     Sales,
     Renewal,
@@ -27,6 +39,14 @@ enum Department {
     Hr,
 }
 
+const ALL_DEPARTMENTS: [Department; 5] = [
+    Department::Sales,
+    Department::Renewal,
+    Department::Audit,
+    Department::Developer,
+    Department::Hr,
+];
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct User {
     id: i32,
@@ -48,11 +68,296 @@ struct AssignedCall {
     call_id: i32,
 }
 
+/// A call waiting in a department's backlog because no agent was free to
+/// take it at the time it was generated.
+#[derive(Debug, Clone)]
+struct QueuedCall {
+    call: Call,
+    enqueued_at: Instant,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct QueuedCallView {
+    call: Call,
+    wait_seconds: u64,
+}
+
+/// Events broadcast to `/events` subscribers as they happen.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+enum Event {
+    CallGenerated { call: Call },
+    CallAssigned { user_id: i32, call_id: i32 },
+    UserAvailable { user_id: i32 },
+}
+
+/// Commands accepted by a single agent's mailbox. Each agent task owns its
+/// own `User` and handles these serially, so there's no contention or races
+/// on its state.
+enum AgentCommand {
+    AssignCall {
+        call: Call,
+        reply: oneshot::Sender<bool>,
+    },
+    CompleteCurrentCall {
+        reply: oneshot::Sender<Option<i32>>,
+    },
+    SetStatus {
+        status: Status,
+        /// `true` if the transition was applied, `false` if it was rejected
+        /// because the agent has an active call.
+        reply: oneshot::Sender<bool>,
+    },
+    Snapshot {
+        reply: oneshot::Sender<User>,
+    },
+}
+
+/// How a `GenerateCall` command was resolved. Fed back to the generation
+/// loop's `Throttle` and, for HTTP-originated calls, turned into a status
+/// code.
+enum GenerateCallOutcome {
+    Assigned,
+    Queued,
+    /// `call.id` was already in flight (assigned or queued) when this call
+    /// was generated.
+    Rejected,
+}
+
+/// How a `SetUserStatus` command was resolved, turned into a status code by
+/// `PUT /users/:id/status`.
+#[derive(Debug, PartialEq, Eq)]
+enum SetStatusOutcome {
+    Applied,
+    NotFound,
+    /// The agent has an active call, so the transition was rejected rather
+    /// than silently stranding the dispatcher's `assigned` entry.
+    Busy,
+}
+
+/// Commands accepted by the dispatcher's mailbox. The dispatcher owns the
+/// routing table and department backlogs and is the only task that mutates
+/// them, so HTTP handlers talk to it through request/response pairs instead
+/// of taking locks directly.
+enum DispatcherCommand {
+    GenerateCall {
+        call: Call,
+        /// Replies with how the call was handled. `None` when the caller
+        /// doesn't need the outcome.
+        reply: Option<oneshot::Sender<GenerateCallOutcome>>,
+    },
+    CompleteCall {
+        call_id: i32,
+        reply: oneshot::Sender<bool>,
+    },
+    SetUserStatus {
+        user_id: i32,
+        status: Status,
+        reply: oneshot::Sender<SetStatusOutcome>,
+    },
+    GetUsers {
+        reply: oneshot::Sender<Vec<User>>,
+    },
+    GetCalls {
+        reply: oneshot::Sender<Vec<Call>>,
+    },
+    GetAssignedCalls {
+        reply: oneshot::Sender<Vec<AssignedCall>>,
+    },
+    GetQueue {
+        reply: oneshot::Sender<HashMap<Department, Vec<QueuedCallView>>>,
+    },
+    /// Attempts to drain every department's backlog against currently-free
+    /// agents (a catch-up net for e.g. an agent returning from break), and
+    /// replies with the total number of calls still queued afterward.
+    SweepBacklog {
+        reply: oneshot::Sender<usize>,
+    },
+}
+
+/// Abstracts "now" so the dispatcher's wait-time bookkeeping can be driven
+/// by a fake clock in tests instead of the wall clock.
+trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Abstracts call-id generation so simulations can replay a seeded,
+/// deterministic sequence instead of `rand::rng()`.
+trait IdGenerator: Send + Sync {
+    fn next_id(&self) -> i32;
+}
+
+struct ThreadIdGenerator;
+
+impl IdGenerator for ThreadIdGenerator {
+    fn next_id(&self) -> i32 {
+        rng().random_range(1..9999)
+    }
+}
+
+/// A self-tuning pacer for a polling loop. Feed it how much work happened in
+/// the last pass via `record`, and it adapts the delay before the next pass
+/// toward `target_per_second`, using an exponentially-weighted moving
+/// average of recent passes rather than reacting to one noisy sample: the
+/// delay shrinks while the observed rate is above target (there's a backlog
+/// of work to catch up on) and grows back out while it's below target (the
+/// loop is idle), so throughput self-tunes to offered load.
+struct Throttle {
+    target_per_second: f64,
+    min_delay: Duration,
+    max_delay: Duration,
+    current_delay: Duration,
+    ewma_per_second: f64,
+}
+
+impl Throttle {
+    const EWMA_ALPHA: f64 = 0.3;
+
+    fn new(target_per_second: f64, min_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            target_per_second,
+            min_delay,
+            max_delay,
+            current_delay: max_delay,
+            ewma_per_second: 0.0,
+        }
+    }
+
+    /// Records `n_done` units of work completed over the delay the previous
+    /// `next_delay` call returned.
+    fn record(&mut self, n_done: u32) {
+        let elapsed_secs = self.current_delay.as_secs_f64().max(0.001);
+        let observed_rate = f64::from(n_done) / elapsed_secs;
+        self.ewma_per_second =
+            Self::EWMA_ALPHA * observed_rate + (1.0 - Self::EWMA_ALPHA) * self.ewma_per_second;
+    }
+
+    fn next_delay(&mut self) -> Duration {
+        self.current_delay = if self.ewma_per_second > self.target_per_second {
+            self.current_delay.mul_f64(0.5).max(self.min_delay)
+        } else {
+            self.current_delay.mul_f64(1.5).min(self.max_delay)
+        };
+
+        self.current_delay
+    }
+}
+
+/// Prometheus gauges and counters tracking agent and call throughput,
+/// exposed at `/metrics`.
+struct Metrics {
+    registry: Registry,
+    agents_on_call: IntGauge,
+    agents_available: IntGauge,
+    agents_break: IntGauge,
+    agents_logged_out: IntGauge,
+    queued_calls: IntGaugeVec,
+    calls_generated_total: IntCounter,
+    calls_assigned_total: IntCounter,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+
+        let agents_on_call =
+            IntGauge::new("agents_on_call", "Number of agents currently on a call").unwrap();
+        let agents_available = IntGauge::new(
+            "agents_available",
+            "Number of agents available to take a call",
+        )
+        .unwrap();
+        let agents_break =
+            IntGauge::new("agents_break", "Number of agents currently on break").unwrap();
+        let agents_logged_out =
+            IntGauge::new("agents_logged_out", "Number of agents currently logged out").unwrap();
+        let queued_calls = IntGaugeVec::new(
+            Opts::new("queued_calls", "Number of calls queued per department"),
+            &["department"],
+        )
+        .unwrap();
+        let calls_generated_total = IntCounter::new(
+            "calls_generated_total",
+            "Total number of calls generated since startup",
+        )
+        .unwrap();
+        let calls_assigned_total = IntCounter::new(
+            "calls_assigned_total",
+            "Total number of calls assigned to an agent since startup",
+        )
+        .unwrap();
+
+        registry.register(Box::new(agents_on_call.clone())).unwrap();
+        registry
+            .register(Box::new(agents_available.clone()))
+            .unwrap();
+        registry.register(Box::new(agents_break.clone())).unwrap();
+        registry
+            .register(Box::new(agents_logged_out.clone()))
+            .unwrap();
+        registry.register(Box::new(queued_calls.clone())).unwrap();
+        registry
+            .register(Box::new(calls_generated_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(calls_assigned_total.clone()))
+            .unwrap();
+
+        Self {
+            registry,
+            agents_on_call,
+            agents_available,
+            agents_break,
+            agents_logged_out,
+            queued_calls,
+            calls_generated_total,
+            calls_assigned_total,
+        }
+    }
+
+    /// Recomputes the per-status agent gauges from a fresh snapshot of users.
+    fn refresh_agent_gauges(&self, users: &[User]) {
+        let (mut on_call, mut available, mut break_, mut logged_out) = (0, 0, 0, 0);
+        for user in users {
+            match user.status {
+                Status::OnCall => on_call += 1,
+                Status::Available => available += 1,
+                Status::Break => break_ += 1,
+                Status::LoggedOut => logged_out += 1,
+            }
+        }
+
+        self.agents_on_call.set(on_call);
+        self.agents_available.set(available);
+        self.agents_break.set(break_);
+        self.agents_logged_out.set(logged_out);
+    }
+
+    /// Recomputes the per-department queue length gauge from a fresh
+    /// snapshot of the backlog.
+    fn refresh_queue_gauges(&self, queue: &HashMap<Department, Vec<QueuedCallView>>) {
+        for department in &ALL_DEPARTMENTS {
+            let len = queue.get(department).map_or(0, Vec::len) as i64;
+            self.queued_calls
+                .with_label_values(&[&format!("{:?}", department)])
+                .set(len);
+        }
+    }
+}
+
 lazy_static! {
-    static ref USER_DATA: Arc<RwLock<Vec<User>>> = Arc::new(RwLock::new(create_users()));
-    static ref CALL_DATA: Arc<RwLock<Vec<Call>>> = Arc::new(RwLock::new(Vec::new()));
-    static ref ASSIGNED_CALL_DATA: Arc<RwLock<Vec<AssignedCall>>> =
+    static ref EVENT_SUBSCRIBERS: Arc<RwLock<Vec<mpsc::UnboundedSender<Event>>>> =
         Arc::new(RwLock::new(Vec::new()));
+    static ref METRICS: Metrics = Metrics::new();
+    static ref DISPATCHER_TX: RwLock<Option<mpsc::Sender<DispatcherCommand>>> = RwLock::new(None);
 }
 
 fn create_users() -> Vec<User> {
@@ -90,23 +395,503 @@ fn create_users() -> Vec<User> {
     ]
 }
 
-fn random_id() -> i32 {
-    rng().random_range(1..9999)
+/// Publishes an event to every live subscriber, pruning any whose receiver
+/// has been dropped so dead senders don't accumulate.
+fn publish_event(event: Event) {
+    let mut subscribers = EVENT_SUBSCRIBERS.write().unwrap();
+    subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+}
+
+/// Returns the dispatcher's mailbox. Panics if called before `main` has
+/// started the dispatcher task.
+fn dispatcher_tx() -> mpsc::Sender<DispatcherCommand> {
+    DISPATCHER_TX
+        .read()
+        .unwrap()
+        .clone()
+        .expect("dispatcher not started")
+}
+
+/// Runs a single agent's mailbox loop. The agent owns `user` and
+/// `current_call` exclusively, so every command below is handled without
+/// any locking.
+async fn run_agent(
+    mut user: User,
+    mut current_call: Option<i32>,
+    mut rx: mpsc::Receiver<AgentCommand>,
+) {
+    while let Some(command) = rx.recv().await {
+        match command {
+            AgentCommand::AssignCall { call, reply } => {
+                if user.status == Status::Available {
+                    user.status = Status::OnCall;
+                    current_call = Some(call.id);
+
+                    info!("Assigned Call {} to User {}", call.id, user.name);
+                    publish_event(Event::CallAssigned {
+                        user_id: user.id,
+                        call_id: call.id,
+                    });
+
+                    let _ = reply.send(true);
+                } else {
+                    let _ = reply.send(false);
+                }
+            }
+            AgentCommand::CompleteCurrentCall { reply } => {
+                let completed = current_call.take();
+                if completed.is_some() {
+                    user.status = Status::Available;
+                    info!("User {} is now available again", user.name);
+                    publish_event(Event::UserAvailable { user_id: user.id });
+                }
+                let _ = reply.send(completed);
+            }
+            AgentCommand::SetStatus { status, reply } => {
+                if current_call.is_some() {
+                    info!(
+                        "Rejected status change for User {} ({:?}): agent has an active call",
+                        user.name, status
+                    );
+                    let _ = reply.send(false);
+                } else {
+                    user.status = status;
+                    info!("User {} status set to {:?}", user.name, user.status);
+                    let _ = reply.send(true);
+                }
+            }
+            AgentCommand::Snapshot { reply } => {
+                let _ = reply.send(user.clone());
+            }
+        }
+    }
+}
+
+/// Tries each agent in `dept_agents`, in order, until one accepts the call.
+/// Returns `true` and records the assignment if any agent took it.
+async fn try_assign(
+    call: &Call,
+    dept_agents: &[i32],
+    agent_senders: &HashMap<i32, mpsc::Sender<AgentCommand>>,
+    assigned: &mut HashMap<i32, i32>,
+) -> bool {
+    for agent_id in dept_agents {
+        let Some(tx) = agent_senders.get(agent_id) else {
+            continue;
+        };
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        if tx
+            .send(AgentCommand::AssignCall {
+                call: call.clone(),
+                reply: reply_tx,
+            })
+            .await
+            .is_err()
+        {
+            continue;
+        }
+
+        if let Ok(true) = reply_rx.await {
+            assigned.insert(call.id, *agent_id);
+            METRICS.calls_assigned_total.inc();
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Tries to clear as much of `department`'s backlog as currently-free agents
+/// allow, oldest call first.
+async fn drain_backlog(
+    department: &Department,
+    routing: &HashMap<Department, Vec<i32>>,
+    agent_senders: &HashMap<i32, mpsc::Sender<AgentCommand>>,
+    assigned: &mut HashMap<i32, i32>,
+    backlog: &mut HashMap<Department, VecDeque<QueuedCall>>,
+) {
+    let dept_agents = routing.get(department).cloned().unwrap_or_default();
+
+    if let Some(queue) = backlog.get_mut(department) {
+        while let Some(queued) = queue.front().cloned() {
+            if try_assign(&queued.call, &dept_agents, agent_senders, assigned).await {
+                queue.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+/// Runs the dispatcher: the single task that owns the routing table and
+/// department backlogs, and pushes calls to available agents instead of
+/// mutating shared state directly.
+async fn run_dispatcher(
+    mut rx: mpsc::Receiver<DispatcherCommand>,
+    agent_senders: HashMap<i32, mpsc::Sender<AgentCommand>>,
+    agent_departments: HashMap<i32, Department>,
+    routing: HashMap<Department, Vec<i32>>,
+    clock: Arc<dyn Clock>,
+) {
+    let mut assigned: HashMap<i32, i32> = HashMap::new();
+    let mut backlog: HashMap<Department, VecDeque<QueuedCall>> = HashMap::new();
+    // Call ids currently assigned or queued, keyed independently of
+    // department so a client-supplied id can't collide across departments
+    // and clobber another call's entry in `assigned`.
+    let mut in_flight_ids: HashSet<i32> = HashSet::new();
+
+    while let Some(command) = rx.recv().await {
+        match command {
+            DispatcherCommand::GenerateCall { call, reply } => {
+                if !in_flight_ids.insert(call.id) {
+                    info!(
+                        "Rejected Call {} ({:?}): id already in flight",
+                        call.id, call.department
+                    );
+                    if let Some(reply) = reply {
+                        let _ = reply.send(GenerateCallOutcome::Rejected);
+                    }
+                    continue;
+                }
+
+                info!("New call generated: {:?}", call);
+                METRICS.calls_generated_total.inc();
+                publish_event(Event::CallGenerated { call: call.clone() });
+
+                let department = call.department.clone();
+                drain_backlog(
+                    &department,
+                    &routing,
+                    &agent_senders,
+                    &mut assigned,
+                    &mut backlog,
+                )
+                .await;
+
+                let already_backlogged = backlog.get(&department).is_some_and(|q| !q.is_empty());
+                let assigned_directly = if already_backlogged {
+                    false
+                } else {
+                    let dept_agents = routing.get(&department).cloned().unwrap_or_default();
+                    try_assign(&call, &dept_agents, &agent_senders, &mut assigned).await
+                };
+
+                let outcome = if assigned_directly {
+                    GenerateCallOutcome::Assigned
+                } else {
+                    info!(
+                        "No agent available for Call {} ({:?}), queuing",
+                        call.id, call.department
+                    );
+                    backlog
+                        .entry(department)
+                        .or_default()
+                        .push_back(QueuedCall {
+                            call,
+                            enqueued_at: clock.now(),
+                        });
+                    GenerateCallOutcome::Queued
+                };
+
+                if let Some(reply) = reply {
+                    let _ = reply.send(outcome);
+                }
+            }
+            DispatcherCommand::CompleteCall { call_id, reply } => {
+                let Some(user_id) = assigned.remove(&call_id) else {
+                    let _ = reply.send(false);
+                    continue;
+                };
+                in_flight_ids.remove(&call_id);
+
+                let mut completed = false;
+                if let Some(tx) = agent_senders.get(&user_id) {
+                    let (reply_tx, reply_rx) = oneshot::channel();
+                    if tx
+                        .send(AgentCommand::CompleteCurrentCall { reply: reply_tx })
+                        .await
+                        .is_ok()
+                    {
+                        completed = matches!(reply_rx.await, Ok(Some(_)));
+                    }
+                }
+                let _ = reply.send(completed);
+
+                if completed {
+                    if let Some(department) = agent_departments.get(&user_id).cloned() {
+                        drain_backlog(
+                            &department,
+                            &routing,
+                            &agent_senders,
+                            &mut assigned,
+                            &mut backlog,
+                        )
+                        .await;
+                    }
+                }
+            }
+            DispatcherCommand::SetUserStatus {
+                user_id,
+                status,
+                reply,
+            } => {
+                let Some(tx) = agent_senders.get(&user_id) else {
+                    let _ = reply.send(SetStatusOutcome::NotFound);
+                    continue;
+                };
+
+                let (reply_tx, reply_rx) = oneshot::channel();
+                let applied = tx
+                    .send(AgentCommand::SetStatus {
+                        status,
+                        reply: reply_tx,
+                    })
+                    .await
+                    .is_ok()
+                    && reply_rx.await.unwrap_or(false);
+                let outcome = if applied {
+                    SetStatusOutcome::Applied
+                } else {
+                    SetStatusOutcome::Busy
+                };
+                let _ = reply.send(outcome);
+            }
+            DispatcherCommand::GetUsers { reply } => {
+                let mut users = Vec::with_capacity(agent_senders.len());
+                for tx in agent_senders.values() {
+                    let (reply_tx, reply_rx) = oneshot::channel();
+                    if tx
+                        .send(AgentCommand::Snapshot { reply: reply_tx })
+                        .await
+                        .is_ok()
+                    {
+                        if let Ok(user) = reply_rx.await {
+                            users.push(user);
+                        }
+                    }
+                }
+                users.sort_by_key(|u| u.id);
+                let _ = reply.send(users);
+            }
+            DispatcherCommand::GetCalls { reply } => {
+                let calls = backlog
+                    .values()
+                    .flat_map(|queue| queue.iter().map(|queued| queued.call.clone()))
+                    .collect();
+                let _ = reply.send(calls);
+            }
+            DispatcherCommand::GetAssignedCalls { reply } => {
+                let assigned_calls = assigned
+                    .iter()
+                    .map(|(&call_id, &user_id)| AssignedCall { user_id, call_id })
+                    .collect();
+                let _ = reply.send(assigned_calls);
+            }
+            DispatcherCommand::GetQueue { reply } => {
+                let now = clock.now();
+                let queue = backlog
+                    .iter()
+                    .map(|(department, queued_calls)| {
+                        let views = queued_calls
+                            .iter()
+                            .map(|queued| QueuedCallView {
+                                call: queued.call.clone(),
+                                wait_seconds: now.duration_since(queued.enqueued_at).as_secs(),
+                            })
+                            .collect();
+                        (department.clone(), views)
+                    })
+                    .collect();
+                let _ = reply.send(queue);
+            }
+            DispatcherCommand::SweepBacklog { reply } => {
+                for department in &ALL_DEPARTMENTS {
+                    drain_backlog(
+                        department,
+                        &routing,
+                        &agent_senders,
+                        &mut assigned,
+                        &mut backlog,
+                    )
+                    .await;
+                }
+
+                let remaining = backlog.values().map(VecDeque::len).sum();
+                let _ = reply.send(remaining);
+            }
+        }
+    }
 }
 
 async fn get_users() -> Json<Vec<User>> {
-    let users = USER_DATA.read().unwrap();
-    Json(users.clone())
+    let (reply_tx, reply_rx) = oneshot::channel();
+    dispatcher_tx()
+        .send(DispatcherCommand::GetUsers { reply: reply_tx })
+        .await
+        .unwrap();
+    Json(reply_rx.await.unwrap())
 }
 
 async fn get_calls() -> Json<Vec<Call>> {
-    let calls = CALL_DATA.read().unwrap();
-    Json(calls.clone())
+    let (reply_tx, reply_rx) = oneshot::channel();
+    dispatcher_tx()
+        .send(DispatcherCommand::GetCalls { reply: reply_tx })
+        .await
+        .unwrap();
+    Json(reply_rx.await.unwrap())
 }
 
 async fn get_assigned_calls() -> Json<Vec<AssignedCall>> {
-    let assigned_calls = ASSIGNED_CALL_DATA.read().unwrap();
-    Json(assigned_calls.clone())
+    let (reply_tx, reply_rx) = oneshot::channel();
+    dispatcher_tx()
+        .send(DispatcherCommand::GetAssignedCalls { reply: reply_tx })
+        .await
+        .unwrap();
+    Json(reply_rx.await.unwrap())
+}
+
+async fn get_queue() -> Json<HashMap<Department, Vec<QueuedCallView>>> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    dispatcher_tx()
+        .send(DispatcherCommand::GetQueue { reply: reply_tx })
+        .await
+        .unwrap();
+    Json(reply_rx.await.unwrap())
+}
+
+async fn create_call(Json(call): Json<Call>) -> StatusCode {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    dispatcher_tx()
+        .send(DispatcherCommand::GenerateCall {
+            call,
+            reply: Some(reply_tx),
+        })
+        .await
+        .unwrap();
+
+    match reply_rx.await.unwrap() {
+        GenerateCallOutcome::Rejected => StatusCode::CONFLICT,
+        GenerateCallOutcome::Assigned | GenerateCallOutcome::Queued => StatusCode::CREATED,
+    }
+}
+
+async fn metrics_handler() -> (StatusCode, String) {
+    let (users_tx, users_rx) = oneshot::channel();
+    dispatcher_tx()
+        .send(DispatcherCommand::GetUsers { reply: users_tx })
+        .await
+        .unwrap();
+    let (queue_tx, queue_rx) = oneshot::channel();
+    dispatcher_tx()
+        .send(DispatcherCommand::GetQueue { reply: queue_tx })
+        .await
+        .unwrap();
+
+    METRICS.refresh_agent_gauges(&users_rx.await.unwrap());
+    METRICS.refresh_queue_gauges(&queue_rx.await.unwrap());
+
+    let metric_families = METRICS.registry.gather();
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&metric_families, &mut buffer)
+        .unwrap();
+
+    (StatusCode::OK, String::from_utf8(buffer).unwrap())
+}
+
+async fn update_user_status(Path(user_id): Path<i32>, Json(status): Json<Status>) -> StatusCode {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    dispatcher_tx()
+        .send(DispatcherCommand::SetUserStatus {
+            user_id,
+            status,
+            reply: reply_tx,
+        })
+        .await
+        .unwrap();
+
+    match reply_rx.await.unwrap() {
+        SetStatusOutcome::Applied => StatusCode::OK,
+        SetStatusOutcome::NotFound => StatusCode::NOT_FOUND,
+        SetStatusOutcome::Busy => StatusCode::CONFLICT,
+    }
+}
+
+async fn complete_call(Path(call_id): Path<i32>) -> StatusCode {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    dispatcher_tx()
+        .send(DispatcherCommand::CompleteCall {
+            call_id,
+            reply: reply_tx,
+        })
+        .await
+        .unwrap();
+
+    if reply_rx.await.unwrap() {
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+async fn sse_events() -> Sse<impl Stream<Item = Result<SseEvent, Infallible>>> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    EVENT_SUBSCRIBERS.write().unwrap().push(tx);
+
+    let stream = UnboundedReceiverStream::new(rx)
+        .map(|event| Ok(SseEvent::default().json_data(event).unwrap()));
+
+    Sse::new(stream)
+}
+
+/// Builds the call a given `call_id` would generate, mirroring the
+/// round-robin department assignment the simulator uses.
+fn build_call(call_id: i32) -> Call {
+    Call {
+        id: call_id,
+        details: format!("Call details for ID {}", call_id),
+        department: match call_id % 5 {
+            0 => Department::Sales,
+            1 => Department::Renewal,
+            2 => Department::Audit,
+            3 => Department::Developer,
+            _ => Department::Hr,
+        },
+    }
+}
+
+/// Spawns one agent task per user plus the dispatcher task that routes
+/// calls to them, returning the dispatcher's mailbox.
+fn spawn_dispatch_system(
+    users: Vec<User>,
+    clock: Arc<dyn Clock>,
+) -> mpsc::Sender<DispatcherCommand> {
+    let mut agent_senders = HashMap::new();
+    let mut agent_departments = HashMap::new();
+    let mut routing: HashMap<Department, Vec<i32>> = HashMap::new();
+
+    for user in users {
+        let (tx, rx) = mpsc::channel(32);
+        routing
+            .entry(user.department.clone())
+            .or_default()
+            .push(user.id);
+        agent_departments.insert(user.id, user.department.clone());
+        agent_senders.insert(user.id, tx);
+        tokio::spawn(run_agent(user, None, rx));
+    }
+
+    let (dispatcher_tx, dispatcher_rx) = mpsc::channel(128);
+    tokio::spawn(run_dispatcher(
+        dispatcher_rx,
+        agent_senders,
+        agent_departments,
+        routing,
+        clock,
+    ));
+
+    dispatcher_tx
 }
 
 #[tokio::main]
@@ -115,88 +900,347 @@ async fn main() {
         .filter_level(log::LevelFilter::Info)
         .init();
 
+    let dispatcher_tx = spawn_dispatch_system(create_users(), Arc::new(SystemClock));
+    *DISPATCHER_TX.write().unwrap() = Some(dispatcher_tx.clone());
+
     let create_server = tokio::spawn(async {
         let app = Router::new()
             .route("/", get(|| async { "Hello, World!" }))
             .route("/get-users", get(get_users))
             .route("/get-calls", get(get_calls))
-            .route("/get-assigned-calls", get(get_assigned_calls));
+            .route("/get-assigned-calls", get(get_assigned_calls))
+            .route("/get-queue", get(get_queue))
+            .route("/calls", post(create_call))
+            .route("/users/:id/status", put(update_user_status))
+            .route("/assigned-calls/:call_id/complete", post(complete_call))
+            .route("/events", get(sse_events))
+            .route("/metrics", get(metrics_handler));
 
         let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
         axum::serve(listener, app).await.unwrap();
     });
 
-    let create_call = thread::spawn(|| loop {
-        let call_id = random_id();
-        let new_call = Call {
-            id: call_id,
-            details: format!("Call details for ID {}", call_id),
-            department: match call_id % 5 {
-                0 => Department::Sales,
-                1 => Department::Renewal,
-                2 => Department::Audit,
-                3 => Department::Developer,
-                _ => Department::Hr,
-            },
-        };
+    let sweep_tx = dispatcher_tx.clone();
+    let sweep_loop = tokio::spawn(async move {
+        let mut throttle = Throttle::new(0.1, Duration::from_millis(250), Duration::from_secs(5));
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            sweep_tx
+                .send(DispatcherCommand::SweepBacklog { reply: reply_tx })
+                .await
+                .unwrap();
+            let remaining_backlog = reply_rx.await.unwrap();
 
-        {
-            let mut calls = CALL_DATA.write().unwrap();
-            calls.push(new_call.clone());
+            throttle.record(if remaining_backlog > 0 { 1 } else { 0 });
+            tokio::time::sleep(throttle.next_delay()).await;
         }
+    });
 
-        info!("New call generated: {:?}", new_call);
-        thread::sleep(Duration::from_secs(2));
+    let create_call_loop = tokio::spawn(async move {
+        let id_gen: Arc<dyn IdGenerator> = Arc::new(ThreadIdGenerator);
+        let mut throttle = Throttle::new(0.5, Duration::from_millis(250), Duration::from_secs(2));
+        loop {
+            let new_call = build_call(id_gen.next_id());
+
+            let (reply_tx, reply_rx) = oneshot::channel();
+            dispatcher_tx
+                .send(DispatcherCommand::GenerateCall {
+                    call: new_call,
+                    reply: Some(reply_tx),
+                })
+                .await
+                .unwrap();
+            let outcome = reply_rx.await.unwrap();
+
+            throttle.record(if matches!(outcome, GenerateCallOutcome::Assigned) {
+                1
+            } else {
+                0
+            });
+            tokio::time::sleep(throttle.next_delay()).await;
+        }
     });
 
-    let assign_call = thread::spawn(|| loop {
-        {
-            let mut calls = CALL_DATA.write().unwrap();
-            let mut users = USER_DATA.write().unwrap();
-            let mut assigned_calls = ASSIGNED_CALL_DATA.write().unwrap();
-
-            while let Some(call) = calls.pop() {
-                if let Some(user) = users
-                    .iter_mut()
-                    .find(|u| u.department == call.department && u.status == Status::Available)
-                {
-                    user.status = Status::OnCall;
-                    let assignment = AssignedCall {
-                        user_id: user.id,
-                        call_id: call.id,
-                    };
-                    assigned_calls.push(assignment);
+    create_server.await.unwrap();
+    create_call_loop.await.unwrap();
+    sweep_loop.await.unwrap();
+}
 
-                    info!("Assigned Call {} to User {}", call.id, user.name)
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::sync::Mutex;
+
+    /// A clock that only moves when `advance` is called, so tests can
+    /// deterministically trigger time-dependent behavior like wait times.
+    struct SimClock {
+        base: Instant,
+        advanced: Mutex<Duration>,
+    }
+
+    impl SimClock {
+        fn new() -> Self {
+            Self {
+                base: Instant::now(),
+                advanced: Mutex::new(Duration::ZERO),
             }
         }
 
-        thread::sleep(Duration::from_secs(2));
-    });
+        fn advance(&self, dt: Duration) {
+            *self.advanced.lock().unwrap() += dt;
+        }
+    }
 
-    let reset_status = thread::spawn(|| loop {
-        {
-            let mut users = USER_DATA.write().unwrap();
-            let assigned_calls = ASSIGNED_CALL_DATA.read().unwrap();
+    impl Clock for SimClock {
+        fn now(&self) -> Instant {
+            self.base + *self.advanced.lock().unwrap()
+        }
+    }
 
-            // Find users who are currently on a call
-            let assigned_user_ids: Vec<i32> = assigned_calls.iter().map(|ac| ac.user_id).collect();
+    /// Generates call ids from a seeded RNG so a failing scenario can be
+    /// replayed exactly by reusing the same seed.
+    struct SeededIdGenerator(Mutex<StdRng>);
 
-            for user in users.iter_mut() {
-                if assigned_user_ids.contains(&user.id) {
-                    // Simulate call completion by making the user available again
-                    user.status = Status::Available;
-                    info!("User {} is now available again", user.name)
-                }
+    impl SeededIdGenerator {
+        fn new(seed: u64) -> Self {
+            Self(Mutex::new(StdRng::seed_from_u64(seed)))
+        }
+    }
+
+    impl IdGenerator for SeededIdGenerator {
+        fn next_id(&self) -> i32 {
+            self.0.lock().unwrap().random_range(1..9999)
+        }
+    }
+
+    async fn call_users(dispatcher_tx: &mpsc::Sender<DispatcherCommand>) -> Vec<User> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        dispatcher_tx
+            .send(DispatcherCommand::GetUsers { reply: reply_tx })
+            .await
+            .unwrap();
+        reply_rx.await.unwrap()
+    }
+
+    async fn call_assigned(dispatcher_tx: &mpsc::Sender<DispatcherCommand>) -> Vec<AssignedCall> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        dispatcher_tx
+            .send(DispatcherCommand::GetAssignedCalls { reply: reply_tx })
+            .await
+            .unwrap();
+        reply_rx.await.unwrap()
+    }
+
+    async fn call_queue(
+        dispatcher_tx: &mpsc::Sender<DispatcherCommand>,
+    ) -> HashMap<Department, Vec<QueuedCallView>> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        dispatcher_tx
+            .send(DispatcherCommand::GetQueue { reply: reply_tx })
+            .await
+            .unwrap();
+        reply_rx.await.unwrap()
+    }
+
+    /// Submits `n` seeded calls and asserts that every one ends up either
+    /// assigned to exactly one agent or sitting in a department's backlog
+    /// (never lost), and that no agent holds two concurrent assignments.
+    async fn run_seeded_scenario(seed: u64, n: u32) {
+        let clock = Arc::new(SimClock::new());
+        let dispatcher_tx = spawn_dispatch_system(create_users(), clock.clone());
+        let id_gen = SeededIdGenerator::new(seed);
+
+        let mut generated = Vec::new();
+        for _ in 0..n {
+            let call = build_call(id_gen.next_id());
+            generated.push(call.id);
+            dispatcher_tx
+                .send(DispatcherCommand::GenerateCall { call, reply: None })
+                .await
+                .unwrap();
+        }
+
+        // The dispatcher processes its mailbox in order, so a round-trip
+        // request here only returns once every `GenerateCall` sent above has
+        // been handled. Without this, `clock.advance` below can race ahead
+        // of the dispatcher and every queued call ends up stamped with
+        // `enqueued_at` from *after* the advance.
+        call_assigned(&dispatcher_tx).await;
+
+        clock.advance(Duration::from_secs(30));
+
+        let assigned = call_assigned(&dispatcher_tx).await;
+        let queue = call_queue(&dispatcher_tx).await;
+
+        let mut assigned_user_ids = assigned.iter().map(|ac| ac.user_id).collect::<Vec<_>>();
+        assigned_user_ids.sort_unstable();
+        let mut deduped = assigned_user_ids.clone();
+        deduped.dedup();
+        assert_eq!(
+            assigned_user_ids, deduped,
+            "seed {seed}: an agent was assigned two concurrent calls"
+        );
+
+        let mut seen_ids = assigned.iter().map(|ac| ac.call_id).collect::<Vec<_>>();
+        seen_ids.extend(queue.values().flatten().map(|view| view.call.id));
+        seen_ids.sort_unstable();
+        let mut expected_ids = generated.clone();
+        expected_ids.sort_unstable();
+        assert_eq!(
+            seen_ids, expected_ids,
+            "seed {seed}: a generated call was lost"
+        );
+
+        for views in queue.values() {
+            if let Some(oldest) = views.iter().max_by_key(|view| view.wait_seconds) {
+                assert!(
+                    oldest.wait_seconds >= 30,
+                    "seed {seed}: oldest queued call's wait time didn't advance with the clock"
+                );
             }
         }
+    }
 
-        thread::sleep(Duration::from_secs(10)); // Reset users every 5 seconds
-    });
+    #[tokio::test]
+    async fn no_call_is_ever_lost_across_seeds() {
+        for seed in 0..5 {
+            run_seeded_scenario(seed, 25).await;
+        }
+    }
 
-    create_server.await.unwrap();
-    create_call.join().unwrap();
-    assign_call.join().unwrap();
-    reset_status.join().unwrap();
+    #[tokio::test]
+    async fn agents_on_break_stop_receiving_calls() {
+        let clock = Arc::new(SimClock::new());
+        let dispatcher_tx = spawn_dispatch_system(create_users(), clock.clone());
+
+        // Alice (id 1, Sales) goes on break; Sales calls should now queue.
+        let (reply_tx, reply_rx) = oneshot::channel();
+        dispatcher_tx
+            .send(DispatcherCommand::SetUserStatus {
+                user_id: 1,
+                status: Status::Break,
+                reply: reply_tx,
+            })
+            .await
+            .unwrap();
+        assert_eq!(reply_rx.await.unwrap(), SetStatusOutcome::Applied);
+
+        let sales_call = Call {
+            id: 9001,
+            details: "test call".to_string(),
+            department: Department::Sales,
+        };
+        dispatcher_tx
+            .send(DispatcherCommand::GenerateCall {
+                call: sales_call,
+                reply: None,
+            })
+            .await
+            .unwrap();
+
+        let users = call_users(&dispatcher_tx).await;
+        let alice = users.iter().find(|u| u.id == 1).unwrap();
+        assert_eq!(alice.status, Status::Break);
+
+        let queue = call_queue(&dispatcher_tx).await;
+        assert_eq!(queue.get(&Department::Sales).map(Vec::len), Some(1));
+    }
+
+    #[tokio::test]
+    async fn set_status_rejected_for_busy_agent() {
+        let clock = Arc::new(SimClock::new());
+        let dispatcher_tx = spawn_dispatch_system(create_users(), clock.clone());
+
+        let sales_call = Call {
+            id: 9002,
+            details: "test call".to_string(),
+            department: Department::Sales,
+        };
+        dispatcher_tx
+            .send(DispatcherCommand::GenerateCall {
+                call: sales_call,
+                reply: None,
+            })
+            .await
+            .unwrap();
+
+        let users = call_users(&dispatcher_tx).await;
+        let alice = users.iter().find(|u| u.id == 1).unwrap();
+        assert_eq!(alice.status, Status::OnCall);
+
+        // Forcing Alice back to Available while she's on a call must be
+        // rejected, not leave her double-booked against a fresh assignment.
+        let (reply_tx, reply_rx) = oneshot::channel();
+        dispatcher_tx
+            .send(DispatcherCommand::SetUserStatus {
+                user_id: 1,
+                status: Status::Available,
+                reply: reply_tx,
+            })
+            .await
+            .unwrap();
+        assert_eq!(reply_rx.await.unwrap(), SetStatusOutcome::Busy);
+
+        let users = call_users(&dispatcher_tx).await;
+        let alice = users.iter().find(|u| u.id == 1).unwrap();
+        assert_eq!(alice.status, Status::OnCall);
+
+        let assigned = call_assigned(&dispatcher_tx).await;
+        assert_eq!(
+            assigned.iter().filter(|ac| ac.user_id == 1).count(),
+            1,
+            "Alice should still hold exactly one assignment"
+        );
+    }
+
+    #[tokio::test]
+    async fn duplicate_call_id_is_rejected() {
+        let clock = Arc::new(SimClock::new());
+        let dispatcher_tx = spawn_dispatch_system(create_users(), clock.clone());
+
+        let first = Call {
+            id: 42,
+            details: "test call".to_string(),
+            department: Department::Sales,
+        };
+        let second = Call {
+            id: 42,
+            details: "test call".to_string(),
+            department: Department::Renewal,
+        };
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        dispatcher_tx
+            .send(DispatcherCommand::GenerateCall {
+                call: first,
+                reply: Some(reply_tx),
+            })
+            .await
+            .unwrap();
+        assert!(matches!(
+            reply_rx.await.unwrap(),
+            GenerateCallOutcome::Assigned
+        ));
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        dispatcher_tx
+            .send(DispatcherCommand::GenerateCall {
+                call: second,
+                reply: Some(reply_tx),
+            })
+            .await
+            .unwrap();
+        assert!(matches!(
+            reply_rx.await.unwrap(),
+            GenerateCallOutcome::Rejected
+        ));
+
+        let assigned = call_assigned(&dispatcher_tx).await;
+        assert_eq!(
+            assigned.iter().filter(|ac| ac.call_id == 42).count(),
+            1,
+            "the rejected duplicate must not clobber the original assignment"
+        );
+    }
 }